@@ -0,0 +1,247 @@
+//! Implementation of [`FrameAllocator`] which controls all the frames in the
+//! operating system, plus a global reference-count table that lets several
+//! page tables share one physical frame (copy-on-write).
+//!
+//! 物理页帧分配器的实现，以及一张全局的引用计数表，使得多个页表可以共享
+//! 同一个物理页帧（写时复制用）。
+
+use super::{PhysAddr, PhysPageNum};
+use crate::config::MEMORY_END;
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt::{self, Debug, Formatter};
+use lazy_static::*;
+
+/// manage a frame which has the same lifecycle as the tracker
+pub struct FrameTracker {
+    /// physical page number
+    pub ppn: PhysPageNum,
+}
+
+impl FrameTracker {
+    /// Create a new, freshly-zeroed `FrameTracker`. This is the path used by
+    /// `frame_alloc`: the frame starts out uniquely owned (refcount 1).
+    pub fn new(ppn: PhysPageNum) -> Self {
+        let bytes_array = ppn.get_bytes_array();
+        for i in bytes_array {
+            *i = 0;
+        }
+        frame_ref_inc(ppn);
+        Self { ppn }
+    }
+    /// Wrap an already-allocated `ppn` that is being shared with another
+    /// `FrameTracker` (a COW clone), bumping its refcount without touching
+    /// its contents. The frame is only actually freed once every tracker
+    /// sharing it has been dropped.
+    pub fn new_shared(ppn: PhysPageNum) -> Self {
+        frame_ref_inc(ppn);
+        Self { ppn }
+    }
+}
+
+impl Debug for FrameTracker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("FrameTracker:PPN={:#x}", self.ppn.0))
+    }
+}
+
+impl Drop for FrameTracker {
+    fn drop(&mut self) {
+        if frame_ref_dec(self.ppn) == 0 {
+            frame_dealloc(self.ppn);
+        }
+    }
+}
+
+trait FrameAllocator {
+    fn new() -> Self;
+    fn alloc(&mut self) -> Option<PhysPageNum>;
+    fn dealloc(&mut self, ppn: PhysPageNum);
+}
+
+/// an implementation for frame allocator
+pub struct StackFrameAllocator {
+    current: usize,
+    end: usize,
+    recycled: Vec<usize>,
+}
+
+impl StackFrameAllocator {
+    /// 设置分配器能够管理的物理页号区间 [l, r)
+    pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.current = l.0;
+        self.end = r.0;
+    }
+    /// Try to carve `count` physically contiguous frames, aligned to
+    /// `1 << align_log2` pages, out of the unallocated `[current, end)`
+    /// region first: `current` is advanced past any alignment padding, and
+    /// the skipped frames are pushed onto `recycled` rather than lost. If
+    /// there isn't room left in the bump region, falls back to scanning
+    /// `recycled` for an aligned contiguous block before giving up.
+    fn alloc_contiguous(&mut self, count: usize, align_log2: usize) -> Option<Vec<usize>> {
+        if count == 0 {
+            return Some(Vec::new());
+        }
+        let align = 1usize << align_log2;
+        let aligned_current = (self.current + align - 1) / align * align;
+        if aligned_current.checked_add(count)? <= self.end {
+            for padding in self.current..aligned_current {
+                self.recycled.push(padding);
+            }
+            self.current = aligned_current + count;
+            return Some((aligned_current..aligned_current + count).collect());
+        }
+        let mut sorted = self.recycled.clone();
+        sorted.sort_unstable();
+        for start in 0..sorted.len() {
+            if sorted[start] % align != 0 || start + count > sorted.len() {
+                continue;
+            }
+            let run: Vec<usize> = (sorted[start]..sorted[start] + count).collect();
+            if run[..] == sorted[start..start + count] {
+                self.recycled.retain(|ppn| !run.contains(ppn));
+                return Some(run);
+            }
+        }
+        None
+    }
+    /// Counterpart to `alloc_contiguous`: hand a contiguous run of frames
+    /// back to the allocator one at a time.
+    fn dealloc_contiguous(&mut self, ppns: &[usize]) {
+        for &ppn in ppns {
+            self.dealloc(PhysPageNum(ppn));
+        }
+    }
+}
+impl FrameAllocator for StackFrameAllocator {
+    fn new() -> Self {
+        Self {
+            current: 0,
+            end: 0,
+            recycled: Vec::new(),
+        }
+    }
+    fn alloc(&mut self) -> Option<PhysPageNum> {
+        if let Some(ppn) = self.recycled.pop() {
+            Some(ppn.into())
+        } else if self.current == self.end {
+            None
+        } else {
+            self.current += 1;
+            Some((self.current - 1).into())
+        }
+    }
+    fn dealloc(&mut self, ppn: PhysPageNum) {
+        let ppn = ppn.0;
+        // validity check
+        if ppn >= self.current || self.recycled.iter().any(|&v| v == ppn) {
+            panic!("Frame ppn={:#x} has not been allocated!", ppn);
+        }
+        // recycle
+        self.recycled.push(ppn);
+    }
+}
+
+type FrameAllocatorImpl = StackFrameAllocator;
+
+lazy_static! {
+    /// frame allocator instance through lazy_static!
+    pub static ref FRAME_ALLOCATOR: UPSafeCell<FrameAllocatorImpl> =
+        unsafe { UPSafeCell::new(FrameAllocatorImpl::new()) };
+    /// How many `FrameTracker`s currently point at a given frame. A frame is
+    /// only handed back to the allocator once its count reaches zero, which
+    /// is what lets copy-on-write pages be shared by parent and child until
+    /// one of them writes to it.
+    static ref FRAME_REF_COUNT: UPSafeCell<BTreeMap<usize, usize>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+/// initiate the frame allocator using `ekernel` and `MEMORY_END`
+pub fn init_frame_allocator() {
+    extern "C" {
+        fn ekernel();
+    }
+    FRAME_ALLOCATOR.exclusive_access().init(
+        PhysAddr::from(ekernel as usize).ceil(),
+        PhysAddr::from(MEMORY_END).floor(),
+    );
+}
+
+/// allocate a frame
+pub fn frame_alloc() -> Option<FrameTracker> {
+    FRAME_ALLOCATOR
+        .exclusive_access()
+        .alloc()
+        .map(FrameTracker::new)
+}
+
+/// deallocate a frame
+fn frame_dealloc(ppn: PhysPageNum) {
+    FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
+}
+
+/// Allocate `count` physically contiguous frames aligned to `1 << align_log2`
+/// pages (e.g. `align_log2 = 9` for a 2MiB megapage run, `18` for 1GiB) —
+/// meant as a prerequisite for mapping huge pages or handing a future DMA
+/// buffer a run it can address as one block. Every frame comes back zeroed,
+/// same as `frame_alloc`. Returns `None` if no sufficiently aligned
+/// contiguous run is available.
+///
+/// Currently unused: the huge-page path (`MemorySet::push_identical_huge`)
+/// only ever maps `Identical` regions, where `ppn == vpn` already and no
+/// frame allocation is needed at all. There is no `Framed` huge-page caller
+/// to actually draw on this yet.
+#[allow(unused)]
+pub fn frame_alloc_contiguous(count: usize, align_log2: usize) -> Option<Vec<FrameTracker>> {
+    let ppns = FRAME_ALLOCATOR
+        .exclusive_access()
+        .alloc_contiguous(count, align_log2)?;
+    Some(
+        ppns.into_iter()
+            .map(|ppn| FrameTracker::new(PhysPageNum(ppn)))
+            .collect(),
+    )
+}
+
+/// Counterpart to `frame_alloc_contiguous` for frames that were never
+/// wrapped in a `FrameTracker` (a `FrameTracker`'s own `Drop` already
+/// deallocates one frame at a time, refcount permitting). Unused for the
+/// same reason `frame_alloc_contiguous` is.
+#[allow(unused)]
+pub fn frame_dealloc_contiguous(ppns: &[PhysPageNum]) {
+    let raw: Vec<usize> = ppns.iter().map(|ppn| ppn.0).collect();
+    FRAME_ALLOCATOR.exclusive_access().dealloc_contiguous(&raw);
+}
+
+/// Record a new share of `ppn` (e.g. a COW clone starting to point at it
+/// without its own freshly-allocated frame).
+pub fn frame_ref_inc(ppn: PhysPageNum) {
+    let mut counts = FRAME_REF_COUNT.exclusive_access();
+    *counts.entry(ppn.0).or_insert(0) += 1;
+}
+
+/// Drop a share of `ppn`, returning the number of owners left. The frame is
+/// freed by the caller (see `FrameTracker::drop`) once this reaches zero.
+pub fn frame_ref_dec(ppn: PhysPageNum) -> usize {
+    let mut counts = FRAME_REF_COUNT.exclusive_access();
+    let count = counts
+        .get_mut(&ppn.0)
+        .expect("frame_ref_dec of a frame with no recorded owners");
+    *count -= 1;
+    let remaining = *count;
+    if remaining == 0 {
+        counts.remove(&ppn.0);
+    }
+    remaining
+}
+
+/// How many owners `ppn` currently has (0 if no `FrameTracker` holds it; a
+/// frame held by a single `FrameTracker` has count 1).
+pub fn frame_ref_count(ppn: PhysPageNum) -> usize {
+    FRAME_REF_COUNT
+        .exclusive_access()
+        .get(&ppn.0)
+        .copied()
+        .unwrap_or(0)
+}
@@ -1,5 +1,5 @@
 //! Implementation of [`MapArea`] and [`MemorySet`].
-use super::{frame_alloc, FrameTracker};
+use super::{frame_alloc, frame_ref_count, FrameTracker};
 use super::{PTEFlags, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange};
@@ -10,7 +10,6 @@ use crate::sync::UPSafeCell;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use core::arch::asm;
 use lazy_static::*;
 use riscv::register::satp;
 
@@ -71,15 +70,49 @@ impl MemorySet {
         //也需要用到这个映射关系来找到向哪些物理页帧上拷贝初始数据。
     }
     
-    /// 删除
+    /// Insert a demand-paged `Framed` area: the range and permission are
+    /// recorded, but no frame is allocated until a page fault touches it.
+    /// 插入一个延迟分配的帧映射区，范围和权限会被记录，但物理帧要等到第一次
+    /// 缺页异常发生时才会分配。
+    pub fn insert_framed_lazy_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+    ) {
+        self.push(
+            MapArea::new_lazy(start_va, end_va, MapType::Framed, permission),
+            None,
+        );
+    }
+
+    /// Like [`Self::insert_framed_lazy_area`], but marks the area as having
+    /// been created by [`Self::mmap`] so [`Self::munmap`] knows it's safe to
+    /// tear down. Only `mmap` itself should call this.
+    fn insert_mmap_area(&mut self, start_va: VirtAddr, end_va: VirtAddr, permission: MapPermission) {
+        let mut area = MapArea::new_lazy(start_va, end_va, MapType::Framed, permission);
+        area.mmap = true;
+        self.push(area, None);
+    }
+
+    /// Remove the area spanning exactly `[start_va, end_va)` (used e.g. to
+    /// tear down a `KernelStack`). Looks up the real area in `self.areas`
+    /// and unmaps *that*, instead of a throwaway stand-in area that would
+    /// always report `lazy == false` and panic trying to unmap a
+    /// lazily-mapped page that was never populated.
     pub fn delete_framed_area(
         &mut self,
         start_va: VirtAddr,
         end_va: VirtAddr,
     ) {
-        self.pop(
-            MapArea::new(start_va, end_va, MapType::Framed, MapPermission { bits: 0 })
-        )
+        let start_vpn = start_va.floor();
+        let end_vpn = end_va.ceil();
+        if let Some(index) = self.areas.iter().position(|area| {
+            area.vpn_range.get_start() == start_vpn && area.vpn_range.get_end() == end_vpn
+        }) {
+            let mut area = self.areas.remove(index);
+            area.unmap(&mut self.page_table);
+        }
     }
 
     fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
@@ -90,15 +123,6 @@ impl MemorySet {
         self.areas.push(map_area);
     }
 
-    fn pop(&mut self, mut map_area: MapArea) {
-        println!("---------{:?}", self.page_table.frames);
-        map_area.unmap(&mut self.page_table);
-        println!("---------{:?}", self.page_table.frames);
-        if let Some(index) = self.areas.iter().position(|area| area == &map_area) {
-            self.areas.remove(index);
-        }
-    }
-
 
     /// Mention that trampoline is not collected by areas. 这不会被 areas 中的逻辑段收集
     fn map_trampoline(&mut self) {  // 用于执行用户程序的代码。这个方法将 trampoline 映射到地址空间中，以便用户程序可以执行。
@@ -108,6 +132,56 @@ impl MemorySet {
             PTEFlags::R | PTEFlags::X,
         );
     }
+    /// Map `[start_va, end_va)` as `Identical`, splitting it into the
+    /// largest aligned gigapage/megapage/4KiB pieces it allows instead of
+    /// one `MapArea` per 4KiB page. This is what shrinks the kernel's own
+    /// page table from thousands of frames down to a handful and cuts TLB
+    /// pressure, since `ekernel..MEMORY_END` and the kernel's own sections
+    /// are large and mostly gigapage/megapage-aligned.
+    /// 把 [start_va, end_va) 按照能对齐的最大粒度（1GiB/2MiB/4KiB）切成若干段
+    /// 分别映射，而不是逐个 4KiB 页建立逻辑段，从而大幅减少内核页表占用的帧数。
+    fn push_identical_huge(&mut self, start_va: usize, end_va: usize, perm: MapPermission) {
+        let mut va = start_va;
+        while va < end_va {
+            let remaining = end_va - va;
+            let level = if va % PageLevel::Giga1G.align_bytes() == 0
+                && remaining >= PageLevel::Giga1G.align_bytes()
+            {
+                Some(PageLevel::Giga1G)
+            } else if va % PageLevel::Mega2M.align_bytes() == 0
+                && remaining >= PageLevel::Mega2M.align_bytes()
+            {
+                Some(PageLevel::Mega2M)
+            } else {
+                None
+            };
+            match level {
+                Some(level) => {
+                    let end = va + level.align_bytes();
+                    self.push(
+                        MapArea::new_huge(va.into(), end.into(), MapType::Identical, perm, level),
+                        None,
+                    );
+                    va = end;
+                }
+                None => {
+                    // not (yet) aligned for a huge page: map a single 4KiB
+                    // page and try again from the next address, which will
+                    // eventually hit a gigapage/megapage boundary.
+                    let next_giga = (va / PageLevel::Giga1G.align_bytes() + 1)
+                        * PageLevel::Giga1G.align_bytes();
+                    let next_mega = (va / PageLevel::Mega2M.align_bytes() + 1)
+                        * PageLevel::Mega2M.align_bytes();
+                    let end = next_giga.min(next_mega).min(end_va);
+                    self.push(
+                        MapArea::new(va.into(), end.into(), MapType::Identical, perm),
+                        None,
+                    );
+                    va = end;
+                }
+            }
+        }
+    }
     /// Without kernel stacks.
     /// 生成内核的地址空间
     pub fn new_kernel() -> Self {
@@ -123,54 +197,30 @@ impl MemorySet {
             sbss_with_stack as usize, ebss as usize
         );
         info!("mapping .text section");
-        memory_set.push(
-            MapArea::new(
-                (stext as usize).into(),
-                (etext as usize).into(),
-                MapType::Identical,
-                MapPermission::R | MapPermission::X,
-            ),
-            None,
+        memory_set.push_identical_huge(
+            stext as usize,
+            etext as usize,
+            MapPermission::R | MapPermission::X,
         );
         info!("mapping .rodata section");
-        memory_set.push(
-            MapArea::new(
-                (srodata as usize).into(),
-                (erodata as usize).into(),
-                MapType::Identical,
-                MapPermission::R,
-            ),
-            None,
-        );
+        memory_set.push_identical_huge(srodata as usize, erodata as usize, MapPermission::R);
         info!("mapping .data section");
-        memory_set.push(
-            MapArea::new(
-                (sdata as usize).into(),
-                (edata as usize).into(),
-                MapType::Identical,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
+        memory_set.push_identical_huge(
+            sdata as usize,
+            edata as usize,
+            MapPermission::R | MapPermission::W,
         );
         info!("mapping .bss section");
-        memory_set.push(
-            MapArea::new(
-                (sbss_with_stack as usize).into(),
-                (ebss as usize).into(),
-                MapType::Identical,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
+        memory_set.push_identical_huge(
+            sbss_with_stack as usize,
+            ebss as usize,
+            MapPermission::R | MapPermission::W,
         );
         info!("mapping physical memory");
-        memory_set.push(
-            MapArea::new(
-                (ekernel as usize).into(),
-                MEMORY_END.into(),
-                MapType::Identical,
-                MapPermission::R | MapPermission::W,
-            ),
-            None,
+        memory_set.push_identical_huge(
+            ekernel as usize,
+            MEMORY_END,
+            MapPermission::R | MapPermission::W,
         );
         memory_set
     }
@@ -230,9 +280,10 @@ impl MemorySet {
             ),
             None,
         );
-        // used in sbrk
+        // used in sbrk: lazy, so growing the heap only reserves the range
+        // and a page is only actually allocated once the program touches it.
         memory_set.push(
-            MapArea::new(
+            MapArea::new_lazy(
                 user_stack_top.into(),
                 user_stack_top.into(),
                 MapType::Framed,
@@ -256,19 +307,185 @@ impl MemorySet {
             elf.header.pt2.entry_point() as usize,
         )
     }
+    /// Clone `parent` for a cheap `fork`: the page-table structure is
+    /// duplicated, but every writable `Framed` page is shared copy-on-write
+    /// instead of copied outright. `W` is cleared and the COW bit set in
+    /// both parent and child PTEs, and the shared frame's refcount is
+    /// bumped so it's only actually freed once every sharer drops it.
+    ///
+    /// Takes `&mut MemorySet` rather than `&MemorySet`: marking a page COW
+    /// means flipping bits in the *parent's* page table too (clearing `W`,
+    /// setting the COW flag), not just building a new child.
+    /// 为了支持代价低廉的 fork，复制页表结构，但对每个可写的 Framed 页采用
+    /// 写时复制：清除 W 标志、设置 COW 标志位，并增加该物理帧的引用计数。
+    pub fn from_existed_user(parent: &mut MemorySet) -> MemorySet {
+        let mut child = Self::new_bare();
+        child.map_trampoline();
+        let trap_cx_start_vpn = VirtAddr::from(TRAP_CONTEXT_BASE).floor();
+        for area in parent.areas.iter() {
+            let mut new_area = MapArea::new(
+                VirtAddr::from(area.vpn_range.get_start()),
+                VirtAddr::from(area.vpn_range.get_end()),
+                area.map_type,
+                area.map_perm,
+            );
+            new_area.lazy = area.lazy;
+            new_area.page_level = area.page_level;
+            new_area.mmap = area.mmap;
+            child.areas.push(new_area);
+            if area.map_type != MapType::Framed {
+                // Identical areas (kernel sections) already point at the same
+                // physical memory in every address space; nothing to share.
+                continue;
+            }
+            // The trap-context page is read/written via `get_trap_cx`, a
+            // direct `ppn.get_mut()` dereference that never goes through the
+            // MMU — it can never take the COW fault that would split a
+            // shared frame apart, so sharing it would let the child silently
+            // clobber the parent's trap context (and vice versa) the moment
+            // either one traps. Duplicate it eagerly instead, same as an
+            // `Identical` area.
+            if area.vpn_range.get_start() == trap_cx_start_vpn {
+                for (&vpn, frame) in area.data_frames.iter() {
+                    let new_frame = frame_alloc().unwrap();
+                    new_frame
+                        .ppn
+                        .get_bytes_array()
+                        .copy_from_slice(frame.ppn.get_bytes_array());
+                    let pte_flags = PTEFlags::from_bits(area.map_perm.bits).unwrap();
+                    child.page_table.map(vpn, new_frame.ppn, pte_flags);
+                    child.areas.last_mut().unwrap().data_frames.insert(vpn, new_frame);
+                }
+                continue;
+            }
+            for (&vpn, frame) in area.data_frames.iter() {
+                let ppn = frame.ppn;
+                let mut pte_flags = PTEFlags::from_bits(area.map_perm.bits).unwrap();
+                if area.map_perm.contains(MapPermission::W) {
+                    pte_flags.remove(PTEFlags::W);
+                    parent.page_table.mark_cow(vpn);
+                    child.page_table.map(vpn, ppn, pte_flags);
+                    child.page_table.mark_cow(vpn);
+                } else {
+                    child.page_table.map(vpn, ppn, pte_flags);
+                }
+                child
+                    .areas
+                    .last_mut()
+                    .unwrap()
+                    .data_frames
+                    .insert(vpn, FrameTracker::new_shared(ppn));
+            }
+        }
+        child
+    }
     /// Change page table by writing satp CSR Register.
     /// 将token写入当前 CPU 的 satp CSR ，从这一刻开始 SV39 分页模式就被启用了，而且 MMU 会使用内核地址空间的多级页表进行地址转换。
     pub fn activate(&self) {
         let satp = self.page_table.token();
         unsafe {
             satp::write(satp);
-            asm!("sfence.vma");
         }
+        self.page_table.flush_all();
     }
     /// Translate a virtual page number to a page table entry
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
+    /// Resolve a page fault at `va` caused by an access of kind `access`
+    /// (one of `MapPermission::R/W/X`). This is the hook the trap handler's
+    /// `LoadPageFault`/`StorePageFault`/`InstructionPageFault` arms call into.
+    ///
+    /// Returns `true` if `va` fell inside a lazily-mapped area and the access
+    /// was permitted by that area's `map_perm` — a frame has been allocated,
+    /// zeroed and mapped in, and the faulting instruction can be retried.
+    /// A store fault on a copy-on-write page is resolved by
+    /// `handle_cow_fault` and also returns `true`.
+    /// Returns `false` if `va` is in no area, the access violates the
+    /// area's permission, or the page is already backed by a frame; the
+    /// caller should treat that as fatal and kill the
+    /// process, since there's no way to distinguish "not mapped yet" from
+    /// "not a valid address" other than this check.
+    /// 根据缺页地址 va 和访问类型 access 尝试按需分配一页；如果 va 不在任何
+    /// 逻辑段内，或者访问类型不被该逻辑段的 map_perm 允许，返回 false，调用方
+    /// 应当认为这是一次非法访问并杀死进程。
+    pub fn handle_page_fault(&mut self, va: VirtAddr, access: MapPermission) -> bool {
+        let vpn = va.floor();
+        if access == MapPermission::W {
+            if let Some(pte) = self.page_table.find_pte(vpn) {
+                if pte.is_valid() && pte.is_cow() {
+                    return self.handle_cow_fault(vpn);
+                }
+            }
+        }
+        let area = match self
+            .areas
+            .iter_mut()
+            .find(|area| area.contains(vpn, access))
+        {
+            Some(area) => area,
+            None => return false,
+        };
+        if area.is_populated(vpn) {
+            // already backed by a frame: this is a genuine fault (e.g. a
+            // permission violation on an eagerly-mapped page), not a lazy one.
+            return false;
+        }
+        area.map_one(&mut self.page_table, vpn);
+        true
+    }
+    /// Resolve a store fault on a COW page at `vpn`: if the underlying frame
+    /// is still shared, allocate a fresh one and copy the old bytes across;
+    /// if this was the last sharer, just hand the original frame back.
+    /// Either way the result is remapped writable with the COW bit cleared.
+    fn handle_cow_fault(&mut self, vpn: VirtPageNum) -> bool {
+        let pte = self.page_table.find_pte(vpn).unwrap();
+        let old_ppn = pte.ppn();
+        let flags = pte.flags();
+        let area = self
+            .areas
+            .iter_mut()
+            .find(|area| area.vpn_range.get_start() <= vpn && vpn < area.vpn_range.get_end())
+            .expect("COW page fault in an area that no longer exists");
+        if frame_ref_count(old_ppn) > 1 {
+            let new_frame = frame_alloc().unwrap();
+            let new_ppn = new_frame.ppn;
+            new_ppn.get_bytes_array().copy_from_slice(old_ppn.get_bytes_array());
+            area.data_frames.insert(vpn, new_frame);
+            // `remap` installs a fresh PTE from `flags` alone, which already
+            // leaves the COW bit (outside `PTEFlags`'s 8 bits) cleared, and
+            // flushes the stale translation if this table is the active one.
+            self.page_table.remap(vpn, new_ppn, flags | PTEFlags::W);
+        } else {
+            // sole owner: no copy needed, just take the write permission
+            // back (the same conditional flush applies).
+            self.page_table.restore_writable(vpn);
+        }
+        true
+    }
+    /// Resolve any copy-on-write pages in `[start, start+len)` before a
+    /// syscall writes into user memory through `copy_to_user`: that helper
+    /// writes via a direct physical dereference of whatever frame the PTE
+    /// currently points at, bypassing the MMU entirely, so it would never
+    /// take the COW fault that splits a still-shared frame apart — it would
+    /// just clobber the parent's (or sibling's) copy in place. Call this
+    /// first so every touched page is uniquely owned and genuinely
+    /// writable by the time the raw copy runs.
+    /// 在 copy_to_user 直接物理写入用户内存之前，先解决 [start, start+len) 内
+    /// 的写时复制页：copy_to_user 绕过了 MMU，永远不会触发分裂共享帧的 COW
+    /// 缺页，所以必须预先调用本方法，确保被写入的页都已是独占、真正可写的。
+    pub fn resolve_cow_range(&mut self, start: usize, len: usize) {
+        let mut vpn = VirtAddr::from(start).floor();
+        let end_vpn = VirtAddr::from(start + len).ceil();
+        while vpn < end_vpn {
+            if let Some(pte) = self.page_table.translate(vpn) {
+                if pte.is_valid() && pte.is_cow() {
+                    self.handle_cow_fault(vpn);
+                }
+            }
+            vpn.step();
+        }
+    }
     /// shrink the area to new_end
     #[allow(unused)]
     pub fn shrink_to(&mut self, start: VirtAddr, new_end: VirtAddr) -> bool {
@@ -284,6 +501,111 @@ impl MemorySet {
         }
     }
 
+    /// Map `len` bytes starting at `start` (a `sys_mmap`-style anonymous
+    /// mapping) with permission bits from `port` (bit0=R, bit1=W, bit2=X;
+    /// `U` is always implied since this is for userspace). Rejects a
+    /// `start` that isn't page-aligned, a `port` with reserved bits set or
+    /// with no permission bits at all, and any request that overlaps an
+    /// already-mapped page. Returns 0 on success, -1 otherwise.
+    /// 对 [start, start+len) 做 sys_mmap 风格的匿名映射：校验对齐、port 合法性，
+    /// 并逐页检查是否与已有映射重叠，任何一项不满足都返回 -1。
+    pub fn mmap(&mut self, start: usize, len: usize, port: usize) -> isize {
+        if start % PAGE_SIZE != 0 || port & !0x7 != 0 || port & 0x7 == 0 {
+            return -1;
+        }
+        let start_va = VirtAddr::from(start);
+        let end_va = VirtAddr::from(start + len);
+        let mut vpn = start_va.floor();
+        let end_vpn = end_va.ceil();
+        while vpn < end_vpn {
+            if self.vpn_reserved_or_mapped(vpn) {
+                return -1;
+            }
+            vpn.step();
+        }
+        let mut perm = MapPermission::U;
+        if port & 0b001 != 0 {
+            perm |= MapPermission::R;
+        }
+        if port & 0b010 != 0 {
+            perm |= MapPermission::W;
+        }
+        if port & 0b100 != 0 {
+            perm |= MapPermission::X;
+        }
+        // lazy: a frame is only allocated once the program actually touches
+        // a given page, via MemorySet::handle_page_fault.
+        self.insert_mmap_area(start_va, end_va, perm);
+        0
+    }
+    /// Unmap `len` bytes starting at `start`. Returns -1 unless every page
+    /// in the range is covered by `mmap`-created area(s) that are
+    /// *themselves* wholly contained in `[start, start+len)` — not merely
+    /// "some mmap area happens to touch this vpn", which would let the
+    /// precheck pass for a munmap that only partially covers a larger mmap
+    /// region (area splitting isn't supported, so such a call must be
+    /// rejected rather than silently leaving the uncovered tail mapped). A
+    /// lazily-mapped page that was reserved by `mmap` but never faulted in
+    /// still counts as mapped here — it has no PTE yet, but it's still this
+    /// range's to tear down. This also rules out tearing down ELF segments,
+    /// the user stack, or the fixed `TRAP_CONTEXT_BASE` page, since those
+    /// are never tagged `mmap`.
+    /// 取消 [start, start+len) 的映射；只有整个区间都被某次 mmap 创建的、且
+    /// 自身完整落在 [start, start+len) 内的逻辑段覆盖时才会真正执行——不支持
+    /// 拆分逻辑段，所以只覆盖了一部分的 munmap 请求会被拒绝，而不是静默地留下
+    /// 未取消映射的尾部；否则返回 -1，这样也不会误删 ELF 段、用户栈或固定的
+    /// TRAP_CONTEXT_BASE 页。
+    pub fn munmap(&mut self, start: usize, len: usize) -> isize {
+        if start % PAGE_SIZE != 0 || len % PAGE_SIZE != 0 {
+            return -1;
+        }
+        let start_va = VirtAddr::from(start);
+        let end_va = VirtAddr::from(start + len);
+        let start_vpn = start_va.floor();
+        let end_vpn = end_va.ceil();
+        let mut vpn = start_vpn;
+        while vpn < end_vpn {
+            let covered = self.areas.iter().any(|area| {
+                area.mmap
+                    && area.contains(vpn, MapPermission::empty())
+                    && area.vpn_range.get_start() >= start_vpn
+                    && area.vpn_range.get_end() <= end_vpn
+            });
+            if !covered {
+                return -1;
+            }
+            vpn.step();
+        }
+        self.unmap_mmap_area(start_vpn, end_vpn);
+        0
+    }
+    /// Tear down every `mmap`-created area lying inside `[start_vpn,
+    /// end_vpn)`, unmapping each one's own real PTEs/frames. Only ever
+    /// called after `munmap`'s precheck has confirmed the whole range is
+    /// `mmap`-covered.
+    fn unmap_mmap_area(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) {
+        let mut i = 0;
+        while i < self.areas.len() {
+            let area = &self.areas[i];
+            if area.mmap
+                && area.vpn_range.get_start() >= start_vpn
+                && area.vpn_range.get_end() <= end_vpn
+            {
+                let mut area = self.areas.remove(i);
+                area.unmap(&mut self.page_table);
+            } else {
+                i += 1;
+            }
+        }
+    }
+    /// Is `vpn` backed by a valid PTE, or at least reserved by some
+    /// (possibly lazy, not-yet-populated) area? Used by `mmap`'s overlap
+    /// check, which (unlike `munmap`'s) must consider every area, not just
+    /// ones `mmap` itself created.
+    fn vpn_reserved_or_mapped(&self, vpn: VirtPageNum) -> bool {
+        self.translate(vpn).map_or(false, |pte| pte.is_valid())
+            || self.areas.iter().any(|area| area.contains(vpn, MapPermission::empty()))
+    }
     /// append the area to new_end
     #[allow(unused)]
     pub fn append_to(&mut self, start: VirtAddr, new_end: VirtAddr) -> bool {
@@ -300,12 +622,61 @@ impl MemorySet {
     }
 }
 /// map area structure, controls a contiguous piece of virtual memory
-/// 映射区结构，控制一块连续的虚拟内存       
+/// 映射区结构，控制一块连续的虚拟内存
 pub struct MapArea {
     vpn_range: VPNRange,    // 迭代器 描述一段虚拟页号的连续区间，表示该逻辑段在地址区间中的位置和长度。
     data_frames: BTreeMap<VirtPageNum, FrameTracker>,   //采用Frame时，保存逻辑->物理的键值对，物理是指实际数据，而不是多级页表的中间节点
     map_type: MapType,  // 映射类型，在下面
     map_perm: MapPermission,    //逻辑段的访问方式，页表项标志位 PTEFlags 的一个子集，仅保留 U/R/W/X 四个标志位，下面的 bitflags! 宏
+    /// true if this area is demand-paged: `map()` records the range but
+    /// installs no PTEs, and frames are only allocated lazily on the first
+    /// page fault that touches a given page (see [`MemorySet::handle_page_fault`]).
+    lazy: bool,
+    /// leaf granularity to map this area at: ordinary 4KiB pages, or (for
+    /// suitably aligned `Identical` regions) 2MiB/1GiB huge pages.
+    page_level: PageLevel,
+    /// true if this area was created by [`MemorySet::mmap`]. Lets
+    /// [`MemorySet::munmap`] tell an actual `mmap` region apart from any
+    /// other `Framed` area (ELF segments, the user stack, the kernel
+    /// stack, `TRAP_CONTEXT_BASE`) that would otherwise also pass a plain
+    /// "is this vpn mapped" check.
+    mmap: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// SV39 leaf granularity: a leaf PTE can appear at the top level (1GiB
+/// gigapage), the middle level (2MiB megapage) or the usual bottom level
+/// (4KiB page).
+pub enum PageLevel {
+    /// 1 GiB gigapage, leaf at level 0
+    Giga1G,
+    /// 2 MiB megapage, leaf at level 1
+    Mega2M,
+    /// ordinary 4 KiB page, leaf at level 2
+    Page4K,
+}
+
+impl PageLevel {
+    /// the `level` argument `PageTable::map_at`/`find_pte_create_at` expect
+    fn level(&self) -> usize {
+        match self {
+            PageLevel::Giga1G => 0,
+            PageLevel::Mega2M => 1,
+            PageLevel::Page4K => 2,
+        }
+    }
+    /// how many ordinary 4KiB frames a single leaf of this size covers
+    fn frames(&self) -> usize {
+        match self {
+            PageLevel::Giga1G => 1 << 18,
+            PageLevel::Mega2M => 1 << 9,
+            PageLevel::Page4K => 1,
+        }
+    }
+    /// required alignment, in bytes, of a virtual/physical address for this level
+    fn align_bytes(&self) -> usize {
+        self.frames() * PAGE_SIZE
+    }
 }
 
 impl PartialEq for MapArea {
@@ -335,8 +706,50 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            lazy: false,
+            page_level: PageLevel::Page4K,
+            mmap: false,
         }
     }
+    /// Like [`Self::new`], but mapped with `page_level`-sized leaves instead
+    /// of ordinary 4KiB pages. `start_va`/`end_va` must already be aligned
+    /// to `page_level`'s page size; only `MapType::Identical` is supported
+    /// since a `Framed` huge page would need a contiguous multi-frame
+    /// allocation (see [`crate::mm::frame_alloc`]'s neighbours).
+    pub fn new_huge(
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_type: MapType,
+        map_perm: MapPermission,
+        page_level: PageLevel,
+    ) -> Self {
+        let mut area = Self::new(start_va, end_va, map_type, map_perm);
+        area.page_level = page_level;
+        area
+    }
+    /// Like [`Self::new`], but the area is demand-paged: no frame is
+    /// allocated and no PTE is installed until a page fault touches it.
+    /// 延迟映射版本：只记录范围和权限，不在此时分配物理帧或建立页表项。
+    pub fn new_lazy(
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_type: MapType,
+        map_perm: MapPermission,
+    ) -> Self {
+        let mut area = Self::new(start_va, end_va, map_type, map_perm);
+        area.lazy = true;
+        area
+    }
+    /// Is `vpn` inside this area, and would `access` be permitted by its
+    /// `map_perm`?
+    pub fn contains(&self, vpn: VirtPageNum, access: MapPermission) -> bool {
+        self.vpn_range.get_start() <= vpn && vpn < self.vpn_range.get_end() && self.map_perm.contains(access)
+    }
+    /// Has `vpn` already been populated with a frame? Used to tell a
+    /// legitimately-unpopulated lazy page apart from a genuine fault.
+    pub fn is_populated(&self, vpn: VirtPageNum) -> bool {
+        self.map_type != MapType::Framed || self.data_frames.contains_key(&vpn)
+    }
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         let ppn: PhysPageNum;
         match self.map_type {
@@ -352,25 +765,70 @@ impl MapArea {
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
         page_table.map(vpn, ppn, pte_flags);
     }
-
-    
+    /// Map a single huge-page leaf (`self.page_level`) starting at `vpn`.
+    /// `vpn` must already be aligned to `self.page_level`'s page size; only
+    /// `Identical` mappings are supported (ppn == vpn for the whole run).
+    pub fn map_one_huge(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        let ppn = match self.map_type {
+            MapType::Identical => PhysPageNum(vpn.0),
+            MapType::Framed => panic!("huge framed pages are not supported"),
+        };
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
+        page_table.map_at(vpn, ppn, pte_flags, self.page_level.level());
+    }
 
     #[allow(unused)]
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        let was_populated = self.data_frames.contains_key(&vpn);
         if self.map_type == MapType::Framed {
             self.data_frames.remove(&vpn);
         }
         // 上面是匹配 MapType::Framed 的时候，
         // 而当以恒等映射 Identical 方式映射的时候，物理页号就等于虚拟页号；
+        // a lazy page that was never touched has no PTE to tear down.
+        if self.lazy && !was_populated {
+            return;
+        }
         page_table.unmap(vpn);
     }
     pub fn map(&mut self, page_table: &mut PageTable) {
+        // lazy areas install no PTEs up front; pages are faulted in one at a
+        // time by `MemorySet::handle_page_fault`.
+        if self.lazy {
+            return;
+        }
+        if self.page_level != PageLevel::Page4K {
+            let step = self.page_level.frames();
+            let mut vpn = self.vpn_range.get_start();
+            let end = self.vpn_range.get_end();
+            while vpn < end {
+                self.map_one_huge(page_table, vpn);
+                for _ in 0..step {
+                    vpn.step();
+                }
+            }
+            return;
+        }
         for vpn in self.vpn_range {
             self.map_one(page_table, vpn);
         }
     }
     #[allow(unused)]
     pub fn unmap(&mut self, page_table: &mut PageTable) {
+        if self.page_level != PageLevel::Page4K {
+            // a huge leaf sits at an intermediate level, but `PageTable::unmap`
+            // (via `find_pte`) already stops there, so one call per leaf suffices.
+            let step = self.page_level.frames();
+            let mut vpn = self.vpn_range.get_start();
+            let end = self.vpn_range.get_end();
+            while vpn < end {
+                page_table.unmap(vpn);
+                for _ in 0..step {
+                    vpn.step();
+                }
+            }
+            return;
+        }
         for vpn in self.vpn_range {
             self.unmap_one(page_table, vpn);
         }
@@ -388,8 +846,12 @@ impl MapArea {
 
     #[allow(unused)]
     pub fn append_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
-        for vpn in VPNRange::new(self.vpn_range.get_end(), new_end) {
-            self.map_one(page_table, vpn)
+        // a lazy area (the heap, grown via sbrk) only records the new range;
+        // the newly-covered pages get a frame on their first page fault.
+        if !self.lazy {
+            for vpn in VPNRange::new(self.vpn_range.get_end(), new_end) {
+                self.map_one(page_table, vpn)
+            }
         }
         self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
     }
@@ -454,6 +916,10 @@ pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
 }
 
 /// remap test in kernel space
+///
+/// Works unchanged whether a section ended up gigapage/megapage-mapped or
+/// not: `PageTable::translate` (via `find_pte`) now stops as soon as it hits
+/// a leaf, at whatever level that leaf lives at.
 #[allow(unused)]
 pub fn remap_test() {
     let mut kernel_space = KERNEL_SPACE.exclusive_access();
@@ -477,18 +943,3 @@ pub fn remap_test() {
         .executable(),);
     println!("remap_test passed!");
 }
-
-// pub fn create_framed_area(start: usize, len: usize, port: usize) -> isize{
-//     let inner = None;
-//     let inner = get_current_tasks(inner).
-//     let end = VirtAddr::from(start+len);
-//     let start = VirtAddr::from(start);
-//     let index = start.clone();
-//     while index<end {
-//         if let Some(_) = memoryset.page_table.translate(index.floor()) {
-//             return -1;
-//         } 
-//     }
-//     memoryset.insert_framed_area(start, end, (MapPermission::from_bits((port << 1 | 16) as u8)).unwrap());
-//     0
-// }
@@ -24,10 +24,15 @@ mod page_table;
 
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use address::{StepByOne, VPNRange};
-pub use frame_allocator::{frame_alloc, FrameTracker};
+pub use frame_allocator::{
+    frame_alloc, frame_alloc_contiguous, frame_dealloc_contiguous, frame_ref_count, FrameTracker,
+};
 pub use memory_set::remap_test;
 pub use memory_set::{kernel_stack_position, MapPermission, MemorySet, KERNEL_SPACE};
-pub use page_table::{translated_byte_buffer, translated_va_to_pa, PageTableEntry};
+pub use page_table::{
+    copy_from_user, copy_to_user, translated_byte_buffer, translated_refmut, translated_va_to_pa,
+    PageTableEntry, UserBuffer,
+};
 use page_table::{PTEFlags, PageTable};
 
 /// initiate heap allocator, frame allocator and kernel space
@@ -5,9 +5,13 @@
 // use crate::task::get_current_task;
 
 use super::{frame_alloc, FrameTracker, PhysPageNum, StepByOne, VirtAddr, PhysAddr, VirtPageNum};
+use crate::config::PAGE_SIZE;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
+use core::arch::asm;
+use core::mem::{size_of, MaybeUninit};
+use riscv::register::satp;
 // use riscv::addr::page;
 
 bitflags! {
@@ -24,6 +28,11 @@ bitflags! {
     }
 }
 
+/// Sv39 leaves bits 8-9 of a PTE ("RSW") for supervisor software to use
+/// however it likes; the MMU itself never looks at them. We use bit 8 to
+/// mark a page as copy-on-write.
+const PTE_COW_BIT: usize = 1 << 8;
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 /// page table entry structure
@@ -68,6 +77,33 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    /// Mark this PTE copy-on-write (set the spare RSW bit).
+    pub fn set_cow(&mut self) {
+        self.bits |= PTE_COW_BIT;
+    }
+    /// Clear the copy-on-write mark.
+    pub fn clear_cow(&mut self) {
+        self.bits &= !PTE_COW_BIT;
+    }
+    /// Is this PTE marked copy-on-write?
+    pub fn is_cow(&self) -> bool {
+        self.bits & PTE_COW_BIT != 0
+    }
+    /// Clear the `W` flag in place, e.g. when a writable page becomes COW.
+    pub fn clear_writable(&mut self) {
+        self.bits &= !(PTEFlags::W.bits as usize);
+    }
+    /// Set the `W` flag in place, e.g. when a COW fault resolves.
+    pub fn set_writable(&mut self) {
+        self.bits |= PTEFlags::W.bits as usize;
+    }
+    /// Is this a leaf PTE (any of R/W/X set)? Sv39 uses an all-zero R/W/X to
+    /// mean "pointer to the next level table", so a huge page (megapage at
+    /// level 1, gigapage at level 0) shows up as a valid leaf before the
+    /// walk reaches level 2.
+    pub fn is_leaf(&self) -> bool {
+        self.is_valid() && (self.readable() || self.writable() || self.executable())
+    }
 }
 
 /// page table structure
@@ -97,12 +133,19 @@ impl PageTable {    //页表
     /// 获取用户空间传递的页表标识符，并构建一个对应的 PageTable 对象。
     /// 虚拟页号（vpn）查找页表项（PageTableEntry）。如果页表项不存在，则创建它。这个方法用于在页表中查找或创建多级页表的中间节点。
     fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        self.find_pte_create_at(vpn, 2)
+    }
+    /// Like `find_pte_create`, but stops at `level` (0 = 1GiB gigapage leaf,
+    /// 1 = 2MiB megapage leaf, 2 = ordinary 4KiB leaf) instead of always
+    /// walking down to level 2, so the leaf PTE can be installed one or two
+    /// levels higher than usual.
+    fn find_pte_create_at(&mut self, vpn: VirtPageNum, level: usize) -> Option<&mut PageTableEntry> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
         let mut result: Option<&mut PageTableEntry> = None;
         for (i, idx) in idxs.iter().enumerate() {
             let pte = &mut ppn.get_pte_array()[*idx];
-            if i == 2 {
+            if i == level {
                 result = Some(pte);
                 break;
             }
@@ -117,7 +160,11 @@ impl PageTable {    //页表
     }
     /// Find PageTableEntry by VirtPageNum
     /// 根据虚拟页号（vpn）查找页表项，但不创建新的页表项。这个方法用于查找页表项是否已经存在，以决定是否可以进行映射或解除映射操作。
-    fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    ///
+    /// `pub(crate)` so that `memory_set` can reach in and flip bits (COW
+    /// sharing, permission fixups) in place without going through `map`'s
+    /// "not yet mapped" assertion.
+    pub(crate) fn find_pte(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
         let mut result: Option<&mut PageTableEntry> = None;
@@ -130,6 +177,12 @@ impl PageTable {    //页表
             if !pte.is_valid() {
                 return None;
             }
+            if pte.is_leaf() {
+                // a megapage/gigapage leaf at an intermediate level: there is
+                // no lower-level table to descend into, so stop here.
+                result = Some(pte);
+                break;
+            }
             ppn = pte.ppn();
         }
         result
@@ -143,9 +196,16 @@ impl PageTable {    //页表
     /// 将虚拟地址映射到物理地址。
     #[allow(unused)]
     pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.find_pte_create(vpn).unwrap();
+        self.map_at(vpn, ppn, flags, 2)
+    }
+    /// Like `map`, but installs the leaf at `level` (0 = 1GiB gigapage,
+    /// 1 = 2MiB megapage, 2 = ordinary 4KiB page) instead of always at
+    /// level 2. `vpn`/`ppn` must be aligned to that level's page size.
+    pub fn map_at(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags, level: usize) {
+        let pte = self.find_pte_create_at(vpn, level).unwrap();
         assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        self.flush_vpn(vpn);
     }
     /// remove the map between virtual page number and physical page number
     /// 解除虚拟页号（vpn）到物理页号的映射
@@ -154,6 +214,60 @@ impl PageTable {    //页表
         let pte = self.find_pte(vpn).unwrap();
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
+        self.flush_vpn(vpn);
+    }
+    /// Overwrite an already-mapped PTE in place, e.g. to give a COW page its
+    /// own frame back with `W` restored. Unlike `map`, this does not assert
+    /// the PTE was previously invalid.
+    pub fn remap(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let pte = self.find_pte(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is not mapped, cannot remap", vpn);
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        self.flush_vpn(vpn);
+    }
+    /// Clear `W` and set the COW bit on an already-mapped PTE, flushing its
+    /// TLB entry. Used by `from_existed_user` to mark a parent's (or
+    /// child's) writable page copy-on-write, instead of mutating the
+    /// `&mut PageTableEntry` in place and leaving a stale translation
+    /// cached for whichever of the two tables happens to be active.
+    pub(crate) fn mark_cow(&mut self, vpn: VirtPageNum) {
+        let pte = self.find_pte(vpn).unwrap();
+        pte.clear_writable();
+        pte.set_cow();
+        self.flush_vpn(vpn);
+    }
+    /// Give a COW page its own `W` bit back without touching its frame —
+    /// the sole-owner fast path of `MemorySet::handle_cow_fault` — and
+    /// flush its TLB entry via `flush_vpn`, which only does so when this
+    /// table is the one `satp` currently points at.
+    pub(crate) fn restore_writable(&mut self, vpn: VirtPageNum) {
+        let pte = self.find_pte(vpn).unwrap();
+        pte.set_writable();
+        pte.clear_cow();
+        self.flush_vpn(vpn);
+    }
+    /// Flush the stale TLB entry for a single page after changing its PTE —
+    /// but only if this page table is the one `satp` currently points at.
+    /// Mutating an inactive table (e.g. building a child process's address
+    /// space before it's ever scheduled) needs no flush, since nothing has
+    /// cached translations for it yet.
+    fn flush_vpn(&self, vpn: VirtPageNum) {
+        if satp::read().bits() != self.token() {
+            return;
+        }
+        let va = VirtAddr::from(vpn).0;
+        unsafe {
+            asm!("sfence.vma {0}, x0", in(reg) va);
+        }
+    }
+    /// Flush the whole TLB. Used after switching `satp` to a different
+    /// address space entirely, where invalidating one page at a time would
+    /// cost more than a single global flush.
+    #[allow(unused)]
+    pub fn flush_all(&self) {
+        unsafe {
+            asm!("sfence.vma");
+        }
     }
     /// get the page table entry from the virtual page number
     /// 从虚拟页号（vpn）获取页表项，
@@ -205,6 +319,12 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
 /// 我们应该是只需要一个地址就行
 /// 好像也没有什么需要传进来的……
 /// 找到page_table 调用其 translated 时发现需要一个VirtAddr参数
+///
+/// Only ever translates the single page `virt_addr` falls in — a struct
+/// that straddles a page boundary gets silently corrupted by whatever reads
+/// or writes through the resulting pointer. `copy_to_user`/`copy_from_user`
+/// below don't have that problem; prefer those for anything crossing the
+/// kernel/user boundary.
 pub fn translated_va_to_pa(token: usize, virt_addr: VirtAddr) -> Option<PhysAddr> {
     let page_tabel = PageTable::from_token(token);
     if let Some(pte) = page_tabel.translate(virt_addr.clone().floor()) {
@@ -216,6 +336,162 @@ pub fn translated_va_to_pa(token: usize, virt_addr: VirtAddr) -> Option<PhysAddr
     }
 }
 
+/// Translate a user pointer to a struct known to fit within a single page
+/// into a mutable kernel reference. Panics if `ptr` isn't mapped — for a
+/// struct that might straddle a page boundary, use `copy_to_user`/
+/// `copy_from_user` instead, which walk page by page.
+pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
+    let va = VirtAddr::from(ptr as usize);
+    translated_va_to_pa(token, va).unwrap().get_mut()
+}
+
+/// A discontiguous view into user memory, stitched together from the
+/// `Vec<&mut [u8]>` segments `translated_byte_buffer` returns. Gives
+/// syscalls a `len()`/byte-wise-iterator/`write()` API for a user buffer
+/// that may straddle several pages, instead of hand-rolling the segment
+/// bookkeeping at every call site.
+pub struct UserBuffer {
+    /// 各个（可能不连续的）物理内存段
+    pub buffers: Vec<&'static mut [u8]>,
+}
+
+impl UserBuffer {
+    /// Wrap the segments `translated_byte_buffer` returned.
+    pub fn new(buffers: Vec<&'static mut [u8]>) -> Self {
+        Self { buffers }
+    }
+    /// total length across every (possibly discontiguous) segment
+    pub fn len(&self) -> usize {
+        self.buffers.iter().map(|b| b.len()).sum()
+    }
+    /// Is this buffer empty?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Copy `data` into this buffer across however many segments it spans,
+    /// up to `data.len().min(self.len())` bytes. Returns how many bytes
+    /// were actually copied.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        let len = data.len().min(self.len());
+        let mut copied = 0;
+        for segment in self.buffers.iter_mut() {
+            if copied >= len {
+                break;
+            }
+            let chunk = segment.len().min(len - copied);
+            segment[..chunk].copy_from_slice(&data[copied..copied + chunk]);
+            copied += chunk;
+        }
+        copied
+    }
+}
+
+impl IntoIterator for UserBuffer {
+    type Item = *mut u8;
+    type IntoIter = UserBufferIterator;
+    fn into_iter(self) -> Self::IntoIter {
+        UserBufferIterator {
+            buffers: self.buffers,
+            current_buffer: 0,
+            current_idx: 0,
+        }
+    }
+}
+
+/// Byte-wise iterator over a `UserBuffer`'s (possibly discontiguous)
+/// segments, yielding a raw pointer to each byte in turn.
+pub struct UserBufferIterator {
+    buffers: Vec<&'static mut [u8]>,
+    current_buffer: usize,
+    current_idx: usize,
+}
+
+impl Iterator for UserBufferIterator {
+    type Item = *mut u8;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current_buffer >= self.buffers.len() {
+            return None;
+        }
+        let byte = &mut self.buffers[self.current_buffer][self.current_idx] as *mut u8;
+        if self.current_idx + 1 == self.buffers[self.current_buffer].len() {
+            self.current_idx = 0;
+            self.current_buffer += 1;
+        } else {
+            self.current_idx += 1;
+        }
+        Some(byte)
+    }
+}
+
+/// Shared walk for `copy_to_user`/`copy_from_user`: copy `buf.len()` bytes
+/// between `buf` and the user-space range starting at `user_va`, advancing
+/// page by page so a struct straddling a page boundary is handled correctly
+/// instead of silently truncated/corrupted like a single `translate` call
+/// would. `to_user` selects the copy direction; `require` is the set of
+/// PTE flags every page in the range must have (callers pass `U` plus
+/// `R` or `W` as appropriate). Returns `None`, without copying anything
+/// partway, if any page is unmapped or doesn't satisfy `require`.
+fn copy_bytes_with_user(
+    token: usize,
+    user_va: usize,
+    buf: &mut [u8],
+    require: PTEFlags,
+    to_user: bool,
+) -> Option<()> {
+    let page_table = PageTable::from_token(token);
+    let len = buf.len();
+    // First make sure every page in the range is mapped and permitted,
+    // so a failure partway through doesn't leave a partially-written struct.
+    let mut offset = 0;
+    while offset < len {
+        let va = VirtAddr::from(user_va + offset);
+        let pte = page_table.translate(va.floor())?;
+        if (pte.flags() & require) != require {
+            return None;
+        }
+        offset += PAGE_SIZE - va.page_offset();
+    }
+    let mut copied = 0;
+    while copied < len {
+        let va = VirtAddr::from(user_va + copied);
+        let page_off = va.page_offset();
+        let chunk = (PAGE_SIZE - page_off).min(len - copied);
+        let ppn = page_table.translate(va.floor()).unwrap().ppn();
+        let page_bytes = &mut ppn.get_bytes_array()[page_off..page_off + chunk];
+        if to_user {
+            page_bytes.copy_from_slice(&buf[copied..copied + chunk]);
+        } else {
+            buf[copied..copied + chunk].copy_from_slice(page_bytes);
+        }
+        copied += chunk;
+    }
+    Some(())
+}
+
+/// Copy `*value` into the user-space object at `user_ptr`, correctly even if
+/// it straddles a page boundary. Returns `None` (having written nothing) if
+/// any page it touches is unmapped or not `U`+`W` accessible.
+pub fn copy_to_user<T: Copy>(token: usize, user_ptr: *mut T, value: &T) -> Option<()> {
+    let src = unsafe {
+        core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>())
+    };
+    let mut src = src.to_vec();
+    copy_bytes_with_user(token, user_ptr as usize, &mut src, PTEFlags::U | PTEFlags::W, true)
+}
+
+/// Read a `T` out of user space at `user_ptr`, correctly even if it
+/// straddles a page boundary. Returns `None` if any page it touches is
+/// unmapped or not `U`+`R` accessible.
+pub fn copy_from_user<T: Copy>(token: usize, user_ptr: *const T) -> Option<T> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let mut dst = vec![0u8; size_of::<T>()];
+    copy_bytes_with_user(token, user_ptr as usize, &mut dst, PTEFlags::U | PTEFlags::R, false)?;
+    unsafe {
+        core::ptr::copy_nonoverlapping(dst.as_ptr(), value.as_mut_ptr() as *mut u8, size_of::<T>());
+        Some(value.assume_init())
+    }
+}
+
 // 获取当前任务page_table
 // pub fn create_aaa(start: usize, len: usize, port: usize) {
 //     let lifetime = 0;
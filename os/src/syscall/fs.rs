@@ -3,6 +3,8 @@
 // use crate::syscall::SYSCALL_WRITE;
 // use crate::task::add_syscall_count;
 
+use crate::mm::{translated_byte_buffer, UserBuffer};
+use crate::task::current_user_token;
 
 const FD_STDOUT: usize = 1;
 
@@ -16,9 +18,18 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     trace!("kernel: sys_write");
     match fd {
         FD_STDOUT => {
-            let slice = unsafe { core::slice::from_raw_parts(buf, len) };
-            let str = core::str::from_utf8(slice).unwrap();
-            print!("{}", str);
+            // `buf` is a user virtual address; the kernel runs under its own
+            // page table while handling a trap, so dereferencing it directly
+            // (as this used to) reads whatever `buf` happens to mean in
+            // kernel space instead of the caller's actual data. Translate it
+            // through the current task's page table first, same as every
+            // other syscall that touches user memory.
+            let buffers = translated_byte_buffer(current_user_token(), buf, len);
+            let buffer = UserBuffer::new(buffers);
+            for segment in buffer.buffers.iter() {
+                let str = core::str::from_utf8(segment).unwrap();
+                print!("{}", str);
+            }
             len as isize
         }
         _ => {
@@ -3,18 +3,18 @@
 
 use crate::{
     // config::MAX_SYSCALL_NUM,
-    config::PAGE_SIZE,
     // config::MEMORY_END,
     task::{
-        change_program_brk, exit_current_and_run_next, suspend_current_and_run_next,  current_user_token ,TaskInfo, get_current_task_info, create_memory_area, delete_memory_area,
+        change_program_brk, exit_current_and_run_next, suspend_current_and_run_next,  current_user_token ,TaskInfo, get_current_task_info, current_task_mmap, current_task_munmap, current_task_ensure_writable,
     },
-    timer::get_time_us, 
-    mm::translated_va_to_pa, // mm::translated_byte_buffer // ,get_time_ms,
+    timer::get_time_us,
+    mm::copy_to_user, // mm::translated_byte_buffer // ,get_time_ms,
     // mm::create_framed_area,
 };
+use core::mem::size_of;
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct TimeVal {
     pub sec: usize,     // 存储秒数部分的时间值。
     pub usec: usize,    // 在某些情况下需要更精确的时间度量，例如微秒
@@ -57,16 +57,19 @@ pub fn sys_yield() -> isize {
 pub fn sys_get_time(ts: *mut TimeVal, _tz: usize) -> isize {   //所以这个_tz到底是干啥用的
     trace!("kernel: sys_get_time");
     let us = get_time_us();
-    if let Some(ts) = translated_va_to_pa(current_user_token(), (ts as usize).into()) {
-        let ts = ts.get_mut();
-        // unsafe 在这里提示没用了？
-        *ts = TimeVal {
-            sec: us / 1_000_000,
-            usec: us % 1_000_000,
-            };
-        0
-    } else {
-        -1
+    let value = TimeVal {
+        sec: us / 1_000_000,
+        usec: us % 1_000_000,
+    };
+    // ts may land on a page a `fork`ed child still shares COW with its
+    // parent; split it before copy_to_user's direct physical write would
+    // otherwise clobber the parent's copy in place.
+    current_task_ensure_writable(ts as usize, size_of::<TimeVal>());
+    // copy_to_user walks page by page, so this is correct even if `ts`
+    // straddles a page boundary (translated_va_to_pa silently wasn't).
+    match copy_to_user(current_user_token(), ts, &value) {
+        Some(()) => 0,
+        None => -1,
     }
 }
 
@@ -78,36 +81,27 @@ pub fn sys_get_time(ts: *mut TimeVal, _tz: usize) -> isize {   //所以这个_tz
 /// 提示：如果 [`TaskInfo`] 被分成两页怎么办？
 pub fn sys_task_info(ti: *mut TaskInfo) -> isize {
     trace!("kernel: sys_task_info NOT IMPLEMENTED YET!");
-    if let Some(ti) = translated_va_to_pa(current_user_token(), (ti as usize).into()) {
-        let ti = ti.get_mut();
-        *ti = get_current_task_info();
-        0
-    } else {
-        -1
+    let info = get_current_task_info();
+    current_task_ensure_writable(ti as usize, size_of::<TaskInfo>());
+    match copy_to_user(current_user_token(), ti, &info) {
+        Some(()) => 0,
+        None => -1,
     }
-    
 }
 
 
 // YOUR JOB: Implement mmap.
+// alignment/port/overlap validation all now lives in MemorySet::mmap, via
+// TaskControlBlock::mmap; this is just the syscall-level passthrough.
 pub fn sys_mmap(start: usize, len: usize, port: usize) -> isize {
-    trace!("kernel: sys_mmap NOT IMPLEMENTED YET!");
-    if start%PAGE_SIZE==0 && port&!0x7==0 && port&0x7!=0 {
-        create_memory_area(start, len, port)
-    } else {
-        -1
-    }
+    trace!("kernel: sys_mmap");
+    current_task_mmap(start, len, port)
 }
 
 // YOUR JOB: Implement munmap.
 pub fn sys_munmap(start: usize, len: usize) -> isize {
-    trace!("kernel: sys_munmap NOT IMPLEMENTED YET!");
-    if start%PAGE_SIZE==0 && len%PAGE_SIZE==0 {
-        delete_memory_area(start, len)
-    } else {
-        -1
-    }
-
+    trace!("kernel: sys_munmap");
+    current_task_munmap(start, len)
 }
 /// change data segment size
 pub fn sys_sbrk(size: i32) -> isize {
@@ -7,8 +7,101 @@ use crate::config::MAX_SYSCALL_NUM;
 use crate::mm::{
     kernel_stack_position, MapPermission, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE,
 };
+use crate::sync::UPSafeCell;
 // use crate::timer::get_time;
 use crate::trap::{trap_handler, TrapContext};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// Pid allocator — a stack allocator mirroring `frame_allocator`'s
+/// `StackFrameAllocator`: a monotonically increasing counter plus a
+/// `recycled` freelist, just counting pids instead of physical frames.
+struct PidAllocator {
+    current: usize,
+    recycled: Vec<usize>,
+}
+
+impl PidAllocator {
+    fn new() -> Self {
+        Self {
+            current: 0,
+            recycled: Vec::new(),
+        }
+    }
+    fn alloc(&mut self) -> PidHandle {
+        if let Some(pid) = self.recycled.pop() {
+            PidHandle(pid)
+        } else {
+            self.current += 1;
+            PidHandle(self.current - 1)
+        }
+    }
+    fn dealloc(&mut self, pid: usize) {
+        assert!(pid < self.current);
+        assert!(
+            !self.recycled.iter().any(|recycled_pid| *recycled_pid == pid),
+            "pid {} has been deallocated twice!",
+            pid
+        );
+        self.recycled.push(pid);
+    }
+}
+
+lazy_static! {
+    static ref PID_ALLOCATOR: UPSafeCell<PidAllocator> =
+        unsafe { UPSafeCell::new(PidAllocator::new()) };
+}
+
+/// A pid, RAII-scoped: allocated by `pid_alloc`, returned to the allocator
+/// when dropped, same spirit as `FrameTracker` for physical frames.
+pub struct PidHandle(pub usize);
+
+impl Drop for PidHandle {
+    fn drop(&mut self) {
+        PID_ALLOCATOR.exclusive_access().dealloc(self.0);
+    }
+}
+
+/// Allocate a fresh, never-currently-in-use pid.
+pub fn pid_alloc() -> PidHandle {
+    PID_ALLOCATOR.exclusive_access().alloc()
+}
+
+/// A kernel stack mapped at its pid's slot in kernel space (see
+/// `kernel_stack_position`), torn down automatically when the owning task
+/// is dropped.
+pub struct KernelStack {
+    pid: usize,
+}
+
+impl KernelStack {
+    /// Map a fresh kernel stack for `pid_handle`'s pid.
+    pub fn new(pid_handle: &PidHandle) -> Self {
+        let pid = pid_handle.0;
+        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(pid);
+        KERNEL_SPACE.exclusive_access().insert_framed_area(
+            kernel_stack_bottom.into(),
+            kernel_stack_top.into(),
+            MapPermission::R | MapPermission::W,
+        );
+        Self { pid }
+    }
+    /// The address just past the top of this stack (what `sp` should be
+    /// initialized to).
+    pub fn top(&self) -> usize {
+        kernel_stack_position(self.pid).1
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(self.pid);
+        KERNEL_SPACE
+            .exclusive_access()
+            .delete_framed_area(kernel_stack_bottom.into(), kernel_stack_top.into());
+    }
+}
 
 /// The task control block (TCB) of a task.
 pub struct TaskControlBlock {
@@ -39,6 +132,23 @@ pub struct TaskControlBlock {
     /// 记录起始时间
     pub time_lastcall: usize,
 
+    /// This task's pid. Stable for its whole lifetime; a `fork`ed child
+    /// gets a freshly allocated one of its own.
+    pub pid: PidHandle,
+
+    /// Kernel stack mapped at this pid's slot in kernel space.
+    pub kernel_stack: KernelStack,
+
+    /// The task that `fork`ed this one, if any. `Weak` so a parent and its
+    /// children don't keep each other alive forever once both have exited.
+    pub parent: Option<Weak<UPSafeCell<TaskControlBlock>>>,
+
+    /// Tasks this one has `fork`ed that haven't been reaped by `waitpid` yet.
+    pub children: Vec<Arc<UPSafeCell<TaskControlBlock>>>,
+
+    /// This task's exit code once it has become a zombie (`task_status ==
+    /// Exited`); read by the parent's `waitpid`.
+    pub exit_code: i32,
 }
 
 impl TaskControlBlock {
@@ -51,7 +161,12 @@ impl TaskControlBlock {
         self.memory_set.token()
     }
     /// Based on the elf info in program, build the contents of task in a new address space
-    pub fn new(elf_data: &[u8], app_id: usize) -> Self {
+    ///
+    /// `app_id` is no longer used to place the kernel stack — that's keyed
+    /// off the freshly allocated pid now, the same as a `fork`ed child's is
+    /// — but the parameter is kept so the loader's existing call sites
+    /// don't need to change.
+    pub fn new(elf_data: &[u8], _app_id: usize) -> Self {
         // memory_set with elf program headers/trampoline/trap context/user stack
         let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
         let trap_cx_ppn = memory_set
@@ -59,13 +174,10 @@ impl TaskControlBlock {
             .unwrap()
             .ppn();
         let task_status = TaskStatus::Ready;
-        // map a kernel-stack in kernel space
-        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(app_id);
-        KERNEL_SPACE.exclusive_access().insert_framed_area(
-            kernel_stack_bottom.into(),
-            kernel_stack_top.into(),
-            MapPermission::R | MapPermission::W,
-        );
+        // map a kernel-stack in kernel space, keyed by this task's pid
+        let pid_handle = pid_alloc();
+        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack_top = kernel_stack.top();
         let task_control_block = Self {
             task_status,
             task_cx: TaskContext::goto_trap_return(kernel_stack_top),
@@ -76,7 +188,11 @@ impl TaskControlBlock {
             program_brk: user_sp,
             syscall_counts: [0u32; MAX_SYSCALL_NUM],
             time_lastcall: 0,
-
+            pid: pid_handle,
+            kernel_stack,
+            parent: None,
+            children: Vec::new(),
+            exit_code: 0,
         };
         // prepare TrapContext in user space
         let trap_cx = task_control_block.get_trap_cx();
@@ -90,6 +206,109 @@ impl TaskControlBlock {
         task_control_block
     }
 
+    /// Duplicate this task into a new child: the address space is cloned
+    /// copy-on-write via `MemorySet::from_existed_user` (so `fork` stays
+    /// cheap regardless of how much of the address space is actually
+    /// touched afterwards), the trap context is copied byte for byte so the
+    /// child resumes exactly where the parent called `fork`, and a fresh
+    /// kernel stack is mapped at the child's own newly allocated pid. The
+    /// child is recorded in `self.children` with `self` as its parent;
+    /// callers are expected to set the child's `trap_cx.x[10]` (`a0`) to 0
+    /// afterwards so `fork` returns 0 in the child and the child's pid in
+    /// the parent, per the usual fork ABI.
+    ///
+    /// This builds the address-space/pid/kernel-stack machinery a real
+    /// `sys_fork` needs, but there is no `sys_fork` yet, nor a ready queue
+    /// for the child to be scheduled on: this codebase is still the
+    /// single-running-task model (one `TaskControlBlock` swapped for
+    /// another by the round-robin switcher), not the ch5 process model with
+    /// a `TaskManager`/`add_task`. Turning this into something a user
+    /// program can actually call needs that scheduler rewrite as its own
+    /// change; until then `fork`/`exec`/`waitpid` are unreachable
+    /// infrastructure, not a finished feature.
+    pub fn fork(self: &Arc<UPSafeCell<Self>>) -> Arc<UPSafeCell<Self>> {
+        let mut parent_inner = self.exclusive_access();
+        let memory_set = MemorySet::from_existed_user(&mut parent_inner.memory_set);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT_BASE).into())
+            .unwrap()
+            .ppn();
+        let pid_handle = pid_alloc();
+        let kernel_stack = KernelStack::new(&pid_handle);
+        let kernel_stack_top = kernel_stack.top();
+        let child = Arc::new(unsafe {
+            UPSafeCell::new(Self {
+                task_status: TaskStatus::Ready,
+                task_cx: TaskContext::goto_trap_return(kernel_stack_top),
+                memory_set,
+                trap_cx_ppn,
+                base_size: parent_inner.base_size,
+                heap_bottom: parent_inner.heap_bottom,
+                program_brk: parent_inner.program_brk,
+                syscall_counts: [0u32; MAX_SYSCALL_NUM],
+                time_lastcall: 0,
+                pid: pid_handle,
+                kernel_stack,
+                parent: Some(Arc::downgrade(self)),
+                children: Vec::new(),
+                exit_code: 0,
+            })
+        });
+        parent_inner.children.push(Arc::clone(&child));
+        let child_trap_cx = child.exclusive_access().get_trap_cx();
+        *child_trap_cx = *parent_inner.get_trap_cx();
+        child_trap_cx.kernel_sp = kernel_stack_top;
+        child
+    }
+
+    /// Replace this task's program with a fresh ELF image in place: keeps
+    /// the same pid and kernel stack, but rebuilds `memory_set` and
+    /// `trap_cx_ppn` from scratch, mirroring the elf-loading half of `new`.
+    /// Same caveat as [`Self::fork`]: there is no `sys_exec` wiring it up yet.
+    pub fn exec(&mut self, elf_data: &[u8]) {
+        let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
+        let trap_cx_ppn = memory_set
+            .translate(VirtAddr::from(TRAP_CONTEXT_BASE).into())
+            .unwrap()
+            .ppn();
+        self.memory_set = memory_set;
+        self.trap_cx_ppn = trap_cx_ppn;
+        self.base_size = user_sp;
+        self.heap_bottom = user_sp;
+        self.program_brk = user_sp;
+        let kernel_stack_top = self.kernel_stack.top();
+        let trap_cx = self.get_trap_cx();
+        *trap_cx = TrapContext::app_init_context(
+            entry_point,
+            user_sp,
+            KERNEL_SPACE.exclusive_access().token(),
+            kernel_stack_top,
+            trap_handler as usize,
+        );
+    }
+
+    /// Reap a zombie (`task_status == Exited`) child matching `pid` (or any
+    /// zombie child if `pid == -1`, matching the `sys_waitpid` convention),
+    /// removing it from `self.children` and returning its `(pid, exit_code)`.
+    /// Returns `None` if no such child currently exists; the caller is
+    /// expected to distinguish "no such child at all" from "exists but
+    /// hasn't exited yet" using `self.children` itself. Same caveat as
+    /// [`Self::fork`]: there is no `sys_waitpid` wiring it up yet.
+    pub fn waitpid(&mut self, pid: isize) -> Option<(usize, i32)> {
+        let idx = self.children.iter().position(|child| {
+            let inner = child.exclusive_access();
+            (pid == -1 || pid as usize == inner.pid.0) && inner.task_status == TaskStatus::Exited
+        })?;
+        let child = self.children.remove(idx);
+        assert_eq!(
+            Arc::strong_count(&child),
+            1,
+            "waited-on child still has other references"
+        );
+        let inner = child.exclusive_access();
+        Some((inner.pid.0, inner.exit_code))
+    }
+
 /// 
 ///这段代码是一个 Rust 结构 TaskControlBlock 的定义，其中包括了一些成员字段和 TaskControlBlock 结构的实现，我将着重分析 new 方法。
 // new 方法的作用是创建一个新的 TaskControlBlock 实例，用于表示一个任务（或进程）的控制块。这个控制块包含了与任务执行相关的各种信息，包括任务的内存空间、上下文信息、堆栈、系统调用计数等。
@@ -104,6 +323,23 @@ impl TaskControlBlock {
 // 最终，new 方法返回一个包含了任务控制信息的 TaskControlBlock 实例，该实例准备好用于执行一个程序，其中包括了程序的内存布局、初始状态和执行环境。这在操作系统中用于创建和管理进程或任务。
 
 
+    /// Create an anonymous mapping of `len` bytes at `start` in this task's
+    /// address space. See `MemorySet::mmap` for the alignment/port/overlap
+    /// validation this is built on; returns -1 on any of those failures.
+    pub fn mmap(&mut self, start: usize, len: usize, port: usize) -> isize {
+        self.memory_set.mmap(start, len, port)
+    }
+    /// Tear down a mapping previously created by `mmap`. See
+    /// `MemorySet::munmap`; returns -1 unless the whole range is mapped.
+    pub fn munmap(&mut self, start: usize, len: usize) -> isize {
+        self.memory_set.munmap(start, len)
+    }
+    /// Resolve any COW pages in `[start, start+len)` before a syscall
+    /// copies into user memory there via `copy_to_user`. See
+    /// `MemorySet::resolve_cow_range`.
+    pub fn ensure_writable(&mut self, start: usize, len: usize) {
+        self.memory_set.resolve_cow_range(start, len);
+    }
     /// change the location of the program break. return None if failed.
     pub fn change_program_brk(&mut self, size: i32) -> Option<usize> {
         let old_break = self.program_brk;
@@ -143,7 +379,7 @@ pub enum TaskStatus {
 #[allow(dead_code)]
 /// crate::task
 /// 没有说明就给他一个说明！
-#[derive(Clone)]
+#[derive(Clone, Copy)]
 pub struct TaskInfo {
     /// Task status in it's life cycle
     pub status: TaskStatus,
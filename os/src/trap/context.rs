@@ -0,0 +1,53 @@
+//! Implementation of [`TrapContext`]
+use riscv::register::sstatus::{self, Sstatus, SPP};
+
+#[repr(C)]
+/// Trap context saved/restored across a user/kernel boundary crossing:
+/// the user's general-purpose registers plus enough kernel-side state
+/// (`kernel_satp`/`kernel_sp`/`trap_handler`) for `__alltraps` to get into
+/// Rust without any other scratch space.
+pub struct TrapContext {
+    /// general regs[0..31]
+    pub x: [usize; 32],
+    /// CSR sstatus
+    pub sstatus: Sstatus,
+    /// CSR sepc
+    pub sepc: usize,
+    /// Addr of Page Table
+    pub kernel_satp: usize,
+    /// kernel stack
+    pub kernel_sp: usize,
+    /// Addr of trap_handler function
+    pub trap_handler: usize,
+}
+
+impl TrapContext {
+    /// set stack pointer to x_2 reg (sp)
+    pub fn set_sp(&mut self, sp: usize) {
+        self.x[2] = sp;
+    }
+    /// Init the trap context an app starts running from: `sepc` at its
+    /// entry point, `sp` at its user stack top, and the kernel-side fields
+    /// `trap_return` needs to get back into the kernel on its first trap.
+    pub fn app_init_context(
+        entry: usize,
+        sp: usize,
+        kernel_satp: usize,
+        kernel_sp: usize,
+        trap_handler: usize,
+    ) -> Self {
+        let mut sstatus = sstatus::read();
+        // set CPU privilege to User after trapping back
+        sstatus.set_spp(SPP::User);
+        let mut cx = Self {
+            x: [0; 32],
+            sstatus,
+            sepc: entry,
+            kernel_satp,
+            kernel_sp,
+            trap_handler,
+        };
+        cx.set_sp(sp);
+        cx
+    }
+}
@@ -0,0 +1,147 @@
+//! Trap handling functionality
+//!
+//! Traps from user space go through `__alltraps` (see `trap.S`), which saves registers
+//! into a [`TrapContext`] and calls [`trap_handler`]. Traps taken while
+//! already in the kernel go to [`trap_from_kernel`] instead, since the
+//! kernel never expects to recover from one.
+
+mod context;
+
+use crate::config::{TRAMPOLINE, TRAP_CONTEXT_BASE};
+use crate::mm::{MapPermission, VirtAddr};
+use crate::syscall::syscall;
+use crate::task::{
+    current_task, current_trap_cx, current_user_token, exit_current_and_run_next,
+    suspend_current_and_run_next,
+};
+use crate::timer::set_next_trigger;
+use core::arch::{asm, global_asm};
+use riscv::register::{
+    mtvec::TrapMode,
+    scause::{self, Exception, Interrupt, Trap},
+    sie, stval, stvec,
+};
+
+global_asm!(include_str!("trap.S"));
+
+/// initialize CSR `stvec` as the entry of `__alltraps`
+pub fn init() {
+    set_kernel_trap_entry();
+}
+
+fn set_kernel_trap_entry() {
+    unsafe {
+        stvec::write(trap_from_kernel as usize, TrapMode::Direct);
+    }
+}
+
+fn set_user_trap_entry() {
+    unsafe {
+        stvec::write(TRAMPOLINE as usize, TrapMode::Direct);
+    }
+}
+
+/// enable timer interrupt in sie CSR
+pub fn enable_timer_interrupt() {
+    unsafe {
+        sie::set_stimer();
+    }
+}
+
+#[no_mangle]
+/// handle an interrupt, exception, or system call from user space
+pub fn trap_handler() -> ! {
+    set_kernel_trap_entry();
+    let scause = scause::read();
+    let stval = stval::read();
+    match scause.cause() {
+        Trap::Exception(Exception::UserEnvCall) => {
+            let mut cx = current_trap_cx();
+            cx.sepc += 4;
+            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]);
+            cx = current_trap_cx();
+            cx.x[10] = result as usize;
+        }
+        Trap::Exception(Exception::StoreFault)
+        | Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::InstructionFault)
+        | Trap::Exception(Exception::InstructionPageFault)
+        | Trap::Exception(Exception::LoadFault)
+        | Trap::Exception(Exception::LoadPageFault) => {
+            // The access kind the fault was taken for determines which
+            // permission `MemorySet::handle_page_fault` checks the area
+            // against: a load/instruction fault can only be resolving a
+            // lazily-mapped page, while a store fault might instead be a
+            // COW page giving up its shared frame.
+            let access = match scause.cause() {
+                Trap::Exception(Exception::StoreFault) | Trap::Exception(Exception::StorePageFault) => {
+                    MapPermission::W
+                }
+                Trap::Exception(Exception::InstructionFault)
+                | Trap::Exception(Exception::InstructionPageFault) => MapPermission::X,
+                _ => MapPermission::R,
+            };
+            let va: VirtAddr = stval.into();
+            let resolved = current_task()
+                .map(|task| task.exclusive_access().memory_set.handle_page_fault(va, access))
+                .unwrap_or(false);
+            if !resolved {
+                println!(
+                    "[kernel] {:?} at va = {:#x}, sepc = {:#x}, core dumped.",
+                    scause.cause(),
+                    stval,
+                    current_trap_cx().sepc,
+                );
+                exit_current_and_run_next();
+            }
+        }
+        Trap::Exception(Exception::IllegalInstruction) => {
+            println!("[kernel] IllegalInstruction in application, core dumped.");
+            exit_current_and_run_next();
+        }
+        Trap::Interrupt(Interrupt::SupervisorTimer) => {
+            set_next_trigger();
+            suspend_current_and_run_next();
+        }
+        _ => {
+            panic!(
+                "Unsupported trap {:?}, stval = {:#x}!",
+                scause.cause(),
+                stval
+            );
+        }
+    }
+    trap_return();
+}
+
+#[no_mangle]
+/// return to user space
+pub fn trap_return() -> ! {
+    set_user_trap_entry();
+    let trap_cx_ptr = TRAP_CONTEXT_BASE;
+    let user_satp = current_user_token();
+    extern "C" {
+        fn __alltraps();
+        fn __restore();
+    }
+    let restore_va = __restore as usize - __alltraps as usize + TRAMPOLINE;
+    unsafe {
+        asm!(
+            "fence.i",
+            "jr {restore_va}",
+            restore_va = in(reg) restore_va,
+            in("a0") trap_cx_ptr,
+            in("a1") user_satp,
+            options(noreturn)
+        );
+    }
+}
+
+#[no_mangle]
+/// handle a trap taken while already running in the kernel — the kernel
+/// never expects to recover from one of these.
+fn trap_from_kernel() -> ! {
+    panic!("a trap from kernel!");
+}
+
+pub use context::TrapContext;